@@ -118,27 +118,81 @@
 //! [4]: https://doc.rust-lang.org/std/sync/struct.Mutex.html
 //! [5]: https://doc.rust-lang.org/std/ops/trait.DerefMut.html
 //! [6]: https://doc.rust-lang.org/std/sync/struct.Arc.html#method.make_mut
+//!
+//! # Cargo features
+//!
+//! - `no_std` drops the dependency on `std`, using `core` and `alloc`
+//!   instead. [`ArcowSwap`] and [`Cache`] are unavailable in this mode,
+//!   since they rely on `std`'s thread-locals and `Mutex`.
+//! - `portable-atomic` swaps the refcount's `AtomicUsize` for
+//!   [`portable_atomic`](https://docs.rs/portable-atomic)'s, which works on
+//!   targets (e.g. single-core microcontrollers, some RISC-V/AVR profiles)
+//!   that lack native atomic CAS/RMW, falling back to a critical section or
+//!   a lock. This doesn't change any of the ordering used below; it only
+//!   changes what backs the atomic operations.
+//! - `critical-section` additionally forces `portable-atomic`'s
+//!   `critical-section` fallback, rather than whatever native atomics the
+//!   host has, so that fallback can be tested on ordinary hosts (backed by
+//!   the `critical-section` crate's `std` implementation in dev-dependencies).
+
+#![cfg_attr(feature = "no_std", no_std)]
+
+#[cfg(feature = "no_std")]
+extern crate alloc;
 
-use std::{
+#[cfg(feature = "no_std")]
+use alloc::boxed::Box;
+
+use core::{
     fmt::{Debug, Display, Formatter, Result as FmtResult},
+    marker::PhantomData,
+    mem::{ManuallyDrop, MaybeUninit},
     ops::{Deref, DerefMut},
-    ptr::NonNull,
-    sync::atomic::{AtomicUsize, Ordering},
+    ptr::{self, NonNull},
+    sync::atomic::Ordering,
 };
+#[cfg(not(feature = "portable-atomic"))]
+use core::sync::atomic::AtomicUsize;
+#[cfg(feature = "portable-atomic")]
+use portable_atomic::AtomicUsize;
+
+// `ArcowSwap`/`Cache` need `std`'s thread-locals and `Mutex`, so they're
+// only available off the `no_std` feature.
+#[cfg(not(feature = "no_std"))]
+mod swap;
+#[cfg(not(feature = "no_std"))]
+pub use swap::{ArcowSwap, Cache};
 
-struct ArcowInner<T: Clone> {
+mod thin;
+pub use thin::ThinArcow;
+
+#[repr(C)]
+struct ArcowInner<T: ?Sized> {
     refcount: AtomicUsize,
     inner: T,
 }
 
+// `NonNull` opts out of `Send`/`Sync` unconditionally, so we have to hand
+// them back explicitly. The bounds mirror `Arc<T>`: cloning an `Arcow` gives
+// another thread shared access to the same `T`, so `T` must be `Sync` (and
+// `Send`, since a clone might end up being the one to drop the last
+// reference and run `T`'s destructor on that thread).
+unsafe impl<T: ?Sized + Send + Sync> Send for Arcow<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for Arcow<T> {}
+
 /// Atomically Reference-counted Copy-On-Write shared pointer.
 ///
+/// `T` is usually `Sized`. `Arcow<T>` doesn't support genuinely unsized `T`
+/// (slices, trait objects): its handle is always a `NonNull<ArcowInner<T>>`,
+/// which would make it a fat pointer for those. For a reference-counted,
+/// copy-on-write slice with a single-word handle, see [`ThinArcow`] instead.
+///
 /// See the [crate documentation](index.html) for more details.
-pub struct Arcow<T: Clone> {
+pub struct Arcow<T: ?Sized> {
     inner: NonNull<ArcowInner<T>>,
 }
 
-impl<T: Debug + Clone> Debug for Arcow<T> {
+impl<T: Debug + ?Sized> Debug for Arcow<T> {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
         let inner = unsafe { self.inner.as_ref() };
         write!(fmt, "Arcow/{}{{",
@@ -149,20 +203,57 @@ impl<T: Debug + Clone> Debug for Arcow<T> {
     }
 }
 
-impl<T: Display + Clone> Display for Arcow<T> {
+impl<T: Display + ?Sized> Display for Arcow<T> {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
         let inner = unsafe { self.inner.as_ref() };
         Display::fmt(&inner.inner, fmt)
     }
 }
 
-impl<T: Clone> Arcow<T> {
+impl<T> Arcow<T> {
     /// Wrap the given value in a new `Arcow`.
     pub fn new(inner: T) -> Arcow<T> {
         let inner = Box::new(ArcowInner { refcount: AtomicUsize::new(1),
                                           inner });
         Arcow { inner: Box::leak(inner).into() }
     }
+    /// If `myself` is the only reference to its wrapped value, moves that
+    /// value out and returns it; otherwise, returns `myself` unchanged.
+    ///
+    /// Unlike [`DerefMut`], this never clones: either you get the original
+    /// value back by move, or you get your `Arcow` back so you can decide
+    /// what to do next (e.g. clone it yourself, or just keep sharing it).
+    pub fn try_unwrap(myself: Arcow<T>) -> Result<T, Arcow<T>> {
+        // `Acquire`, matching `get_mut`: a concurrent drop of the last
+        // sibling reference must synchronize-with this check before we
+        // treat the allocation as exclusively ours to move out of and free.
+        if unsafe { myself.inner.as_ref().refcount.load(Ordering::Acquire) } != 1 {
+            return Err(myself);
+        }
+        let myself = ManuallyDrop::new(myself);
+        let ptr = myself.inner;
+        unsafe {
+            let inner = ptr::read(&ptr.as_ref().inner);
+            // `inner` has already been moved out, and no other `Arcow`
+            // references this allocation, so free it without running
+            // `ArcowInner<T>`'s (i.e. `T`'s) destructor a second time.
+            drop(Box::from_raw(ptr.as_ptr() as *mut MaybeUninit<ArcowInner<T>>));
+            Ok(inner)
+        }
+    }
+    /// Returns the wrapped value, moving it out if `myself` is the only
+    /// reference to it, or cloning it otherwise. Mirrors how
+    /// [`Mutex::into_inner`][std::sync::Mutex::into_inner] destructures
+    /// around its own `Drop` impl.
+    pub fn into_inner(myself: Arcow<T>) -> T where T: Clone {
+        match Arcow::try_unwrap(myself) {
+            Ok(inner) => inner,
+            Err(shared) => (*shared).clone(),
+        }
+    }
+}
+
+impl<T: ?Sized> Arcow<T> {
     /// Returns the number of references that exist to this same wrapped
     /// object.
     ///
@@ -175,9 +266,194 @@ impl<T: Clone> Arcow<T> {
             myself.inner.as_ref().refcount.load(Ordering::Relaxed)
         }
     }
+    /// Consumes the `Arcow`, returning the raw inner pointer without
+    /// touching the reference count. Pairs with [`Arcow::from_raw_inner`].
+    ///
+    /// Used by [`ArcowSwap`] to move an `Arcow` in and out of an atomic slot
+    /// without paying for a clone/drop pair on every swap.
+    ///
+    /// Unused (but kept) under the `no_std` feature, since [`ArcowSwap`]
+    /// isn't available there.
+    #[cfg_attr(feature = "no_std", allow(dead_code))]
+    pub(crate) fn into_raw_inner(self) -> NonNull<ArcowInner<T>> {
+        let ptr = self.inner;
+        core::mem::forget(self);
+        ptr
+    }
+    /// Reconstructs an `Arcow` from a pointer previously produced by
+    /// [`Arcow::into_raw_inner`], without touching the reference count.
+    ///
+    /// # Safety
+    /// `ptr` must point to a live `ArcowInner` that the caller is handing
+    /// off exactly one reference-count unit of ownership for.
+    #[cfg_attr(feature = "no_std", allow(dead_code))]
+    pub(crate) unsafe fn from_raw_inner(ptr: NonNull<ArcowInner<T>>) -> Arcow<T> {
+        Arcow { inner: ptr }
+    }
+    /// Returns the raw inner pointer without consuming the `Arcow` or
+    /// touching the reference count. Intended for pointer-identity
+    /// comparisons only; the pointer must not be dereferenced past the
+    /// `Arcow`'s lifetime.
+    #[cfg_attr(feature = "no_std", allow(dead_code))]
+    pub(crate) fn raw_inner_ptr(&self) -> *mut ArcowInner<T> {
+        self.inner.as_ptr()
+    }
+    /// Returns a mutable reference into the given `Arcow`, without any
+    /// cloning, if it is unique; otherwise, returns `None`.
+    ///
+    /// Unlike [`DerefMut`], this never silently clones `T` to guarantee
+    /// mutability; it's an explicit, allocation-free alternative for
+    /// callers that already know (or want to check) that they hold the
+    /// only reference. Exactly mirrors [`Arc::get_mut`][std::sync::Arc::get_mut].
+    pub fn get_mut(myself: &mut Arcow<T>) -> Option<&mut T> {
+        unsafe {
+            // `Acquire` so that, if we do get a unique view, no read or
+            // write through the returned `&mut T` can be reordered before
+            // this load -- otherwise it could observe stale data written by
+            // whichever thread most recently dropped a sibling reference.
+            if myself.inner.as_ref().refcount.load(Ordering::Acquire) == 1 {
+                Some(&mut myself.inner.as_mut().inner)
+            } else {
+                None
+            }
+        }
+    }
+    /// Converts `myself` into a [`UniqueArcow<T>`] if it is the only
+    /// reference to its wrapped value; otherwise, returns `myself`
+    /// unchanged.
+    pub fn into_unique(myself: Arcow<T>) -> Result<UniqueArcow<T>, Arcow<T>> {
+        // `Acquire`, matching `get_mut`: a concurrent drop of the last
+        // sibling reference must synchronize-with this check before we hand
+        // out a `UniqueArcow` whose `DerefMut` trusts uniqueness blindly.
+        if unsafe { myself.inner.as_ref().refcount.load(Ordering::Acquire) } != 1 {
+            return Err(myself);
+        }
+        let ptr = myself.inner;
+        core::mem::forget(myself);
+        Ok(UniqueArcow { inner: ptr })
+    }
+    /// Borrows `myself` without touching the reference count.
+    ///
+    /// Unlike [`Clone::clone`], this performs no atomic RMW on creation (or
+    /// on drop), at the cost of tying the result to `myself`'s lifetime.
+    /// Useful for passing shared data down a hot call stack without paying
+    /// for a pair of atomic operations at every level.
+    pub fn borrow(myself: &Arcow<T>) -> ArcowBorrow<'_, T> {
+        ArcowBorrow { inner: myself.inner, _lifetime: PhantomData }
+    }
+}
+
+/// A uniquely-owned `Arcow<T>`-in-progress.
+///
+/// `UniqueArcow<T>` statically guarantees a refcount of exactly 1, so its
+/// [`DerefMut`] never has to check the count or clone to stay safe. Build
+/// up a value freely -- construct a large `Map` incrementally, say -- then
+/// call [`UniqueArcow::share`] to convert it into a normal, cheaply-clonable
+/// `Arcow<T>` once you're ready to hand out clones. This is the same shape
+/// as triomphe's and the Rust-for-Linux kernel's `UniqueArc`.
+pub struct UniqueArcow<T: ?Sized> {
+    inner: NonNull<ArcowInner<T>>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for UniqueArcow<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for UniqueArcow<T> {}
+
+impl<T> UniqueArcow<T> {
+    /// Wraps the given value in a new, uniquely-owned `UniqueArcow`.
+    pub fn new(inner: T) -> UniqueArcow<T> {
+        let inner = Box::new(ArcowInner { refcount: AtomicUsize::new(1),
+                                          inner });
+        UniqueArcow { inner: Box::leak(inner).into() }
+    }
+}
+
+impl<T: ?Sized> UniqueArcow<T> {
+    /// Converts this into a normal, shareable `Arcow<T>`. Zero-cost: the
+    /// refcount is already 1, so this is just a change of type.
+    pub fn share(myself: UniqueArcow<T>) -> Arcow<T> {
+        let ptr = myself.inner;
+        core::mem::forget(myself);
+        Arcow { inner: ptr }
+    }
 }
 
-impl<T: Clone> Deref for Arcow<T> {
+impl<T: ?Sized> Deref for UniqueArcow<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe {
+            &self.inner.as_ref().inner
+        }
+    }
+}
+
+impl<T: ?Sized> DerefMut for UniqueArcow<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe {
+            &mut self.inner.as_mut().inner
+        }
+    }
+}
+
+impl<T: ?Sized> Drop for UniqueArcow<T> {
+    fn drop(&mut self) {
+        unsafe {
+            drop(Box::from_raw(self.inner.as_ptr()));
+        }
+    }
+}
+
+/// A borrow of an [`Arcow<T>`][Arcow]'s wrapped value that costs no atomic
+/// operation to create or drop.
+///
+/// Obtained via [`Arcow::borrow`]. Mirrors triomphe's `ArcBorrow`: it
+/// `Deref`s to `T` just like `Arcow<T>` does, but the borrow checker (not a
+/// refcount) is what keeps the underlying allocation alive, so there's
+/// nothing to pay for until you actually want an owning handle again via
+/// [`ArcowBorrow::clone_arcow`].
+pub struct ArcowBorrow<'a, T: ?Sized> {
+    inner: NonNull<ArcowInner<T>>,
+    _lifetime: PhantomData<&'a ArcowInner<T>>,
+}
+
+// Bounds mirror `Arcow<T>`'s own `Send`/`Sync` impls above: `clone_arcow` can
+// produce an `Arcow<T>` on whatever thread holds this borrow, and that
+// `Arcow` might end up being the one to drop the last reference and run
+// `T`'s destructor there, so `T` must be `Send` too (e.g. a `MutexGuard` is
+// `Sync` but not `Send`, precisely because unlocking must stay on the
+// locking thread).
+unsafe impl<'a, T: ?Sized + Send + Sync> Send for ArcowBorrow<'a, T> {}
+unsafe impl<'a, T: ?Sized + Send + Sync> Sync for ArcowBorrow<'a, T> {}
+
+impl<'a, T: ?Sized> ArcowBorrow<'a, T> {
+    /// Upgrades this borrow into an owning [`Arcow<T>`][Arcow], paying for
+    /// the single `fetch_add` that [`Clone::clone`] would have paid up
+    /// front.
+    pub fn clone_arcow(myself: ArcowBorrow<'a, T>) -> Arcow<T> {
+        unsafe {
+            myself.inner.as_ref().refcount.fetch_add(1, Ordering::Acquire);
+        }
+        Arcow { inner: myself.inner }
+    }
+}
+
+impl<'a, T: ?Sized> Clone for ArcowBorrow<'a, T> {
+    fn clone(&self) -> ArcowBorrow<'a, T> {
+        *self
+    }
+}
+
+impl<'a, T: ?Sized> Copy for ArcowBorrow<'a, T> {}
+
+impl<'a, T: ?Sized> Deref for ArcowBorrow<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe {
+            &self.inner.as_ref().inner
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for Arcow<T> {
     type Target = T;
     fn deref(&self) -> &T {
         unsafe {
@@ -197,7 +473,7 @@ impl<T: Clone> DerefMut for Arcow<T> {
     }
 }
 
-impl<T: Clone> Clone for Arcow<T> {
+impl<T: ?Sized> Clone for Arcow<T> {
     fn clone(&self) -> Arcow<T> {
         unsafe {
             self.inner.as_ref().refcount.fetch_add(1, Ordering::Acquire);
@@ -206,7 +482,7 @@ impl<T: Clone> Clone for Arcow<T> {
     }
 }
 
-impl<T: Clone> Drop for Arcow<T> {
+impl<T: ?Sized> Drop for Arcow<T> {
     fn drop(&mut self) {
         let old_count = unsafe {
             self.inner.as_ref().refcount.fetch_sub(1, Ordering::Release)
@@ -219,7 +495,10 @@ impl<T: Clone> Drop for Arcow<T> {
     }
 }
 
-#[cfg(test)]
+// The test suite uses `std::rc`/`std::sync` directly, so we skip it under
+// `no_std`; the `critical-section` feature is exercised separately, below,
+// by `critical_section_tests`.
+#[cfg(all(test, not(feature = "no_std")))]
 mod tests {
     use super::*;
     use std::{
@@ -240,6 +519,68 @@ mod tests {
         assert_eq!(Arcow::count(&a), 3);
         assert_eq!(Arcow::count(&d), 1);
     }
+    #[test]
+    fn try_unwrap() {
+        let a = Arcow::new(String::from("hello"));
+        let b = a.clone();
+        let a = match Arcow::try_unwrap(a) {
+            Ok(_) => panic!("try_unwrap should have failed, `b` still exists"),
+            Err(a) => a,
+        };
+        drop(b);
+        match Arcow::try_unwrap(a) {
+            Ok(s) => assert_eq!(s, "hello"),
+            Err(_) => panic!("try_unwrap should have succeeded, unique now"),
+        }
+    }
+    #[test]
+    fn get_mut() {
+        let mut a = Arcow::new(32);
+        let b = a.clone();
+        assert!(Arcow::get_mut(&mut a).is_none());
+        drop(b);
+        *Arcow::get_mut(&mut a).expect("a is unique now") = 64;
+        assert_eq!(*a, 64);
+    }
+    #[test]
+    fn into_inner() {
+        let a = Arcow::new(String::from("hello"));
+        let b = a.clone();
+        assert_eq!(Arcow::into_inner(a), String::from("hello"));
+        assert_eq!(Arcow::into_inner(b), String::from("hello"));
+    }
+    #[test]
+    fn unique() {
+        let mut u = UniqueArcow::new(Vec::new());
+        u.push(1);
+        u.push(2);
+        u.push(3);
+        let a = UniqueArcow::share(u);
+        let b = a.clone();
+        assert_eq!(&*a, &[1, 2, 3]);
+        assert_eq!(Arcow::count(&a), 2);
+        let a = match Arcow::into_unique(a) {
+            Ok(_) => panic!("into_unique should have failed, `b` still exists"),
+            Err(a) => a,
+        };
+        drop(b);
+        let mut u = Arcow::into_unique(a)
+            .unwrap_or_else(|_| panic!("into_unique should have succeeded"));
+        u.push(4);
+        assert_eq!(&*u, &[1, 2, 3, 4]);
+    }
+    #[test]
+    fn borrow() {
+        let a = Arcow::new(String::from("hello"));
+        let borrowed = Arcow::borrow(&a);
+        let borrowed2 = borrowed;
+        assert_eq!(&*borrowed, "hello");
+        assert_eq!(Arcow::count(&a), 1);
+        let b = ArcowBorrow::clone_arcow(borrowed2);
+        assert_eq!(Arcow::count(&a), 2);
+        drop(b);
+        assert_eq!(Arcow::count(&a), 1);
+    }
     /// short for "Unsafe Dropper of Lol".
     /// (it used to be unsafe)
     struct Udl {
@@ -309,3 +650,26 @@ mod tests {
         assert_eq!(*count.lock().unwrap(), 0);
     }
 }
+
+// Exercises the refcount under `portable_atomic`'s `critical-section`
+// fallback, i.e. the backend atomic-less targets actually use. The
+// `critical-section` dev-dependency's `std` feature registers a
+// std-backed implementation so this runs on a normal host.
+#[cfg(all(test, feature = "critical-section"))]
+mod critical_section_tests {
+    use super::*;
+    #[test]
+    fn basic() {
+        let a = Arcow::new(32);
+        let b = a.clone();
+        let mut c = b.clone();
+        *c = 64;
+        assert_eq!(*a, 32);
+        assert_eq!(*b, 32);
+        assert_eq!(*c, 64);
+        assert_eq!(Arcow::count(&a), 2);
+        assert_eq!(Arcow::count(&c), 1);
+        drop(b);
+        assert_eq!(Arcow::count(&a), 1);
+    }
+}
@@ -0,0 +1,260 @@
+//! A genuinely thin (single-word) reference-counted, copy-on-write slice.
+//!
+//! [`Arcow<[U]>`][crate::Arcow] can't fill that role: its handle is a
+//! `NonNull<ArcowInner<[U]>>`, and `ArcowInner<[U]>` is unsized, so the
+//! pointer carries the slice length alongside the address, same as any other
+//! Rust fat pointer to a slice. [`ThinArcow<U>`] instead stores the length in
+//! the allocation's header (ahead of the elements), so the handle itself is
+//! just a `NonNull` to that header -- one word. This is the same trick as
+//! triomphe's and servo_arc's `ThinArc`.
+
+#[cfg(feature = "no_std")]
+use alloc::alloc::{alloc, dealloc, handle_alloc_error};
+#[cfg(not(feature = "no_std"))]
+use std::alloc::{alloc, dealloc, handle_alloc_error};
+
+use core::{
+    alloc::Layout,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+    sync::atomic::Ordering,
+};
+#[cfg(not(feature = "portable-atomic"))]
+use core::sync::atomic::AtomicUsize;
+#[cfg(feature = "portable-atomic")]
+use portable_atomic::AtomicUsize;
+
+/// The header that precedes a `ThinArcow<U>`'s elements in the allocation:
+/// the refcount, plus the element count that an ordinary fat pointer would
+/// otherwise have to carry alongside the address.
+#[repr(C)]
+struct ThinHeader {
+    refcount: AtomicUsize,
+    len: usize,
+}
+
+// `NonNull` opts out of `Send`/`Sync` unconditionally; see `Arcow<T>`'s own
+// impls for the rationale (a clone might end up dropping the last reference
+// and running `U`'s destructor on another thread, so `U` must be `Send` as
+// well as `Sync`).
+unsafe impl<U: Send + Sync> Send for ThinArcow<U> {}
+unsafe impl<U: Send + Sync> Sync for ThinArcow<U> {}
+
+/// A thin (single-word), atomically reference-counted, copy-on-write slice.
+///
+/// See the [module documentation](self) for how this differs from
+/// [`Arcow<[U]>`][crate::Arcow].
+pub struct ThinArcow<U> {
+    header: NonNull<ThinHeader>,
+    _marker: core::marker::PhantomData<U>,
+}
+
+impl<U> ThinArcow<U> {
+    /// Computes the `(full allocation layout, offset of the element array)`
+    /// for `len` elements of `U`, following the header.
+    fn layout_for(len: usize) -> (Layout, usize) {
+        let header_layout = Layout::new::<ThinHeader>();
+        let elems_layout = Layout::array::<U>(len)
+            .expect("ThinArcow<U>: element array too large");
+        let (layout, offset) = header_layout.extend(elems_layout)
+            .expect("ThinArcow<U>: layout computation overflowed");
+        (layout.pad_to_align(), offset)
+    }
+    /// Returns a pointer to the first element, derived from `header` and its
+    /// own (already-initialized) `len`.
+    ///
+    /// # Safety
+    /// `header` must point to a live `ThinHeader` whose `len` has already
+    /// been written.
+    unsafe fn data_ptr(header: NonNull<ThinHeader>) -> *mut U {
+        let len = header.as_ref().len;
+        let (_, offset) = Self::layout_for(len);
+        (header.as_ptr() as *mut u8).add(offset) as *mut U
+    }
+    /// Returns the number of elements.
+    pub fn len(&self) -> usize {
+        unsafe { self.header.as_ref().len }
+    }
+    /// Returns `true` if there are no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Like [`ThinArcow::from_iter`], but takes the element count up front
+    /// instead of trusting `iter`'s `ExactSizeIterator` impl. `iter` must
+    /// yield at least `len` items; extra items are left undrawn, and
+    /// yielding fewer panics.
+    pub fn from_header_and_iter<I>(len: usize, iter: I) -> ThinArcow<U>
+    where
+        I: IntoIterator<Item = U>,
+    {
+        let (layout, offset) = Self::layout_for(len);
+        let raw = unsafe { alloc(layout) };
+        if raw.is_null() {
+            handle_alloc_error(layout);
+        }
+        unsafe {
+            (raw as *mut ThinHeader).write(ThinHeader {
+                refcount: AtomicUsize::new(1),
+                len,
+            });
+        }
+        let data_ptr = unsafe { raw.add(offset) } as *mut U;
+        // Guards the allocation and the elements written so far, so that a
+        // panicking `iter` (or a short one, via the `assert_eq!` below)
+        // doesn't leak the allocation or leave elements double-dropped.
+        struct Guard<U> {
+            raw: *mut u8,
+            layout: Layout,
+            data_ptr: *mut U,
+            written: usize,
+        }
+        impl<U> Drop for Guard<U> {
+            fn drop(&mut self) {
+                unsafe {
+                    for i in 0..self.written {
+                        ptr::drop_in_place(self.data_ptr.add(i));
+                    }
+                    dealloc(self.raw, self.layout);
+                }
+            }
+        }
+        let mut guard = Guard { raw, layout, data_ptr, written: 0 };
+        for item in iter.into_iter().take(len) {
+            unsafe { guard.data_ptr.add(guard.written).write(item); }
+            guard.written += 1;
+        }
+        assert_eq!(guard.written, len,
+                   "ThinArcow::from_header_and_iter: iterator yielded fewer \
+                    than `len` items");
+        core::mem::forget(guard);
+        let header = unsafe { NonNull::new_unchecked(raw as *mut ThinHeader) };
+        ThinArcow { header, _marker: core::marker::PhantomData }
+    }
+}
+
+impl<U: Clone> ThinArcow<U> {
+    /// Builds a `ThinArcow<U>` from an `ExactSizeIterator`, allocating the
+    /// header and the `U` elements in a single block instead of boxing a
+    /// separately-allocated `Vec`/slice.
+    // Deliberately named to match `Arc<[T]>`-adjacent crates (triomphe,
+    // servo_arc); `ThinArcow<U>` can't implement `std::iter::FromIterator`
+    // itself, since that trait's `from_iter` accepts any `IntoIterator`,
+    // while this one needs an `ExactSizeIterator` to size the allocation
+    // up front.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_iter<I>(iter: I) -> ThinArcow<U>
+    where
+        I: IntoIterator<Item = U>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let iter = iter.into_iter();
+        ThinArcow::from_header_and_iter(iter.len(), iter)
+    }
+}
+
+impl<U: Debug> Debug for ThinArcow<U> {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        unsafe {
+            write!(fmt, "ThinArcow/{}{{",
+                   self.header.as_ref().refcount.load(Ordering::Relaxed))?;
+        }
+        Debug::fmt(&**self, fmt)?;
+        write!(fmt, "}}")
+    }
+}
+
+impl<U> Deref for ThinArcow<U> {
+    type Target = [U];
+    fn deref(&self) -> &[U] {
+        unsafe {
+            let data_ptr = Self::data_ptr(self.header);
+            core::slice::from_raw_parts(data_ptr, self.len())
+        }
+    }
+}
+
+impl<U: Clone> DerefMut for ThinArcow<U> {
+    fn deref_mut(&mut self) -> &mut [U] {
+        unsafe {
+            if self.header.as_ref().refcount.load(Ordering::Relaxed) > 1 {
+                *self = ThinArcow::from_iter(self.iter().cloned());
+            }
+            let data_ptr = Self::data_ptr(self.header);
+            core::slice::from_raw_parts_mut(data_ptr, self.len())
+        }
+    }
+}
+
+impl<U> Clone for ThinArcow<U> {
+    fn clone(&self) -> ThinArcow<U> {
+        unsafe {
+            self.header.as_ref().refcount.fetch_add(1, Ordering::Acquire);
+        }
+        ThinArcow { header: self.header, _marker: core::marker::PhantomData }
+    }
+}
+
+impl<U> Drop for ThinArcow<U> {
+    fn drop(&mut self) {
+        unsafe {
+            let old_count = self.header.as_ref().refcount
+                .fetch_sub(1, Ordering::Release);
+            if old_count == 1 {
+                let len = self.header.as_ref().len;
+                let data_ptr = Self::data_ptr(self.header);
+                for i in 0..len {
+                    ptr::drop_in_place(data_ptr.add(i));
+                }
+                let (layout, _) = Self::layout_for(len);
+                dealloc(self.header.as_ptr() as *mut u8, layout);
+            }
+        }
+    }
+}
+
+// Mirrors `lib.rs`'s own test gating: the suite uses `std` directly, so it's
+// skipped under `no_std`.
+#[cfg(all(test, not(feature = "no_std")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_thin() {
+        assert_eq!(
+            core::mem::size_of::<ThinArcow<i32>>(),
+            core::mem::size_of::<usize>(),
+        );
+    }
+
+    #[test]
+    fn slice_basic() {
+        let a: ThinArcow<i32> = ThinArcow::from_iter([1, 2, 3]);
+        let b = a.clone();
+        let mut c = a.clone();
+        c[0] = 100;
+        assert_eq!(&*a, &[1, 2, 3]);
+        assert_eq!(&*b, &[1, 2, 3]);
+        assert_eq!(&*c, &[100, 2, 3]);
+        assert_eq!(a.len(), 3);
+        assert!(!a.is_empty());
+    }
+
+    #[test]
+    fn empty() {
+        let a: ThinArcow<i32> = ThinArcow::from_iter([]);
+        assert!(a.is_empty());
+        assert_eq!(&*a, &[] as &[i32]);
+    }
+
+    #[test]
+    fn drops_elements() {
+        use std::rc::Rc;
+        let canary = Rc::new(());
+        let elems: Vec<Rc<()>> = (0..3).map(|_| canary.clone()).collect();
+        assert_eq!(Rc::strong_count(&canary), 4);
+        let a = ThinArcow::from_iter(elems);
+        drop(a);
+        assert_eq!(Rc::strong_count(&canary), 1);
+    }
+}